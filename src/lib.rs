@@ -16,12 +16,15 @@ extern crate rustc_serialize;
 
 use crypto::digest::Digest;
 use crypto::sha2::Sha256;
+use std::collections::HashMap;
+use std::collections::HashSet;
 use std::fmt;
 
 const LEAF_SIG: u8 = 0u8;
 const INTERNAL_SIG: u8 = 1u8;
 
-type Hash = Vec<u8>;
+/// A single node's hash, stored as raw bytes.
+pub type Hash = Vec<u8>;
 
 /// Merkle Tree is a binary tree, which nodes values are the hash of the
 /// concatenated values of their descendants hashes.
@@ -101,11 +104,140 @@ type Hash = Vec<u8>;
 /// ```
 ///
 /// where `block_index` - index of a block (starts at 0).
-pub struct MerkleTree<H = DefaultHasher> {
+///
+/// # Storage Backend
+///
+/// Internal/leaf nodes live behind the [`NodeStore`] trait rather than a
+/// bare `Vec`, addressed by their breadth-first index. The default
+/// [`InMemoryNodeStore`] keeps today's behavior of holding every node in
+/// memory; a different `S` could back a tree over very large block sets by
+/// an external key-value store instead.
+///
+/// [`NodeStore`]: trait.NodeStore.html
+/// [`InMemoryNodeStore`]: struct.InMemoryNodeStore.html
+pub struct MerkleTree<H = DefaultHasher, S = InMemoryNodeStore> {
     hasher: H,
+    nodes: S,
+    count_internal_nodes: usize,
+    count_leaves: usize,
+}
+
+/// Abstracts the node storage behind a [`MerkleTree`], addressed by
+/// breadth-first index, so a tree can be backed by something other than an
+/// in-memory vector.
+///
+/// [`MerkleTree`]: struct.MerkleTree.html
+pub trait NodeStore {
+    /// Creates a store already populated with `nodes`, addressed by their
+    /// breadth-first index.
+    fn from_nodes(nodes: Vec<Hash>) -> Self where Self: Sized;
+
+    /// Returns the hash stored at breadth-first index `i`.
+    fn get(&self, i: usize) -> &Hash;
+
+    /// Overwrites the hash stored at breadth-first index `i`.
+    fn set(&mut self, i: usize, hash: Hash);
+
+    /// Returns the number of nodes currently stored.
+    fn len(&self) -> usize;
+}
+
+/// The default, in-memory [`NodeStore`], preserving today's behavior of
+/// holding every node of a [`MerkleTree`] in a single `Vec`.
+///
+/// [`NodeStore`]: trait.NodeStore.html
+/// [`MerkleTree`]: struct.MerkleTree.html
+#[derive(Debug, Clone)]
+pub struct InMemoryNodeStore {
     nodes: Vec<Hash>,
+}
+
+impl NodeStore for InMemoryNodeStore {
+    fn from_nodes(nodes: Vec<Hash>) -> InMemoryNodeStore {
+        InMemoryNodeStore { nodes: nodes }
+    }
+
+    fn get(&self, i: usize) -> &Hash {
+        &self.nodes[i]
+    }
+
+    fn set(&mut self, i: usize, hash: Hash) {
+        self.nodes[i] = hash;
+    }
+
+    fn len(&self) -> usize {
+        self.nodes.len()
+    }
+}
+
+/// Tracks which internal nodes are still required to answer an outstanding
+/// [`Proof`]/witness request, so that the rest can be dropped from a
+/// [`NodeStore`] via [`MerkleTree::prune`]. This lets a tree over a very
+/// large or long-lived block set reclaim the space held by historical
+/// internal layers that are no longer queried.
+///
+/// [`Proof`]: struct.Proof.html
+/// [`NodeStore`]: trait.NodeStore.html
+/// [`MerkleTree::prune`]: struct.MerkleTree.html#method.prune
+pub struct MerkleTreePruner {
     count_internal_nodes: usize,
     count_leaves: usize,
+    keep: HashSet<usize>,
+}
+
+impl MerkleTreePruner {
+    /// Creates a pruner for a tree with `count_internal_nodes` internal
+    /// nodes and `count_leaves` leaves. The root is always kept.
+    pub fn new(count_internal_nodes: usize, count_leaves: usize) -> MerkleTreePruner {
+        let mut keep = HashSet::new();
+        keep.insert(0);
+
+        MerkleTreePruner {
+            count_internal_nodes: count_internal_nodes,
+            count_leaves: count_leaves,
+            keep: keep,
+        }
+    }
+
+    /// Marks every node [`MerkleTree::proof`] would read to authenticate
+    /// the leaf at `index`—i.e. its sibling at every level on the way to
+    /// the root—as required, so a later call to [`MerkleTree::prune`] will
+    /// not drop anything needed to answer a proof for it. Out-of-range
+    /// indices are ignored.
+    ///
+    /// [`MerkleTree::proof`]: struct.MerkleTree.html#method.proof
+    /// [`MerkleTree::prune`]: struct.MerkleTree.html#method.prune
+    pub fn keep_leaf(&mut self, index: usize) {
+        if index >= self.count_leaves {
+            return;
+        }
+
+        let mut lvl_start = self.count_internal_nodes;
+        let mut lvl_len = self.count_leaves;
+        let mut lvl_i = index;
+
+        while lvl_len > 1 {
+            let sibling_i = lvl_i ^ 1;
+            let sibling = if sibling_i < lvl_len { sibling_i } else { lvl_i };
+            self.keep.insert(lvl_start + sibling);
+
+            lvl_i /= 2;
+            lvl_len = (lvl_len + 1) / 2;
+            lvl_start -= lvl_len;
+        }
+    }
+
+    /// Overwrites every internal node not kept by a prior [`keep_leaf`] call
+    /// with an empty hash in `nodes`. Leaves are never pruned.
+    ///
+    /// [`keep_leaf`]: #method.keep_leaf
+    fn prune<S: NodeStore>(&self, nodes: &mut S) {
+        for i in 0..self.count_internal_nodes {
+            if !self.keep.contains(&i) {
+                nodes.set(i, Vec::new());
+            }
+        }
+    }
 }
 
 fn hash_leaf_node<T, H>(value: &T, hasher: &mut H) -> Hash
@@ -150,6 +282,50 @@ fn hash_internal_node<H>(left: &Hash, right: &Hash, hasher: &mut H) -> Hash
     result
 }
 
+/// Combines `mine` (the hash at level-relative index `i`) with `other` (its
+/// sibling hash), placing them on the correct side of the concatenation
+/// depending on whether `i` is a left (even) or right (odd) child.
+fn combine_sibling<H>(i: usize, mine: &Hash, other: &Hash, hasher: &mut H) -> Hash
+    where H: Digest
+{
+    if i % 2 == 0 {
+        hash_internal_node(mine, other, hasher)
+    } else {
+        hash_internal_node(other, mine, hasher)
+    }
+}
+
+/// Walks from the leaf at `index` up to the root of a breadth-first `nodes`
+/// store holding `count_leaves` leaves behind `count_internal_nodes`
+/// internal nodes, collecting the sibling hash at each level.
+fn collect_proof_siblings<S: NodeStore>(nodes: &S,
+                                         count_internal_nodes: usize,
+                                         count_leaves: usize,
+                                         index: usize)
+                                         -> Vec<Hash> {
+    let mut lvl_start = count_internal_nodes;
+    let mut lvl_len = count_leaves;
+    let mut lvl_i = index;
+    let mut siblings = Vec::new();
+
+    while lvl_len > 1 {
+        let sibling_i = lvl_i ^ 1;
+        let sibling = if sibling_i < lvl_len {
+            nodes.get(lvl_start + sibling_i).clone()
+        } else {
+            // no sibling at this level, the node was hashed with itself
+            nodes.get(lvl_start + lvl_i).clone()
+        };
+        siblings.push(sibling);
+
+        lvl_i /= 2;
+        lvl_len = (lvl_len + 1) / 2;
+        lvl_start -= lvl_len;
+    }
+
+    siblings
+}
+
 fn build_upper_level<H>(nodes: &[Hash], hasher: &mut H) -> Vec<Hash>
     where H: Digest
 {
@@ -165,51 +341,91 @@ fn build_upper_level<H>(nodes: &[Hash], hasher: &mut H) -> Vec<Hash>
         }
     }
 
-    if row.len() > 1 && row.len() % 2 != 0 {
-        let last_node = row.last().unwrap().clone();
-        row.push(last_node);
-    }
-
     row
 }
 
 fn build_internal_nodes<H>(nodes: &mut Vec<Hash>, count_internal_nodes: usize, hasher: &mut H)
     where H: Digest
 {
+    let count_leaves = nodes.len() - count_internal_nodes;
+    let levels = node_levels(count_internal_nodes, count_leaves);
+
     let mut parents = build_upper_level(&nodes[count_internal_nodes..], hasher);
 
-    let mut upper_level_start = count_internal_nodes - parents.len();
-    let mut upper_level_end = upper_level_start + parents.len();
-    nodes[upper_level_start..upper_level_end].clone_from_slice(&parents);
+    // levels[0] is the leaf level (already in `nodes`); write every level
+    // above it back at its own breadth-first offset, using the same
+    // lvl_start/lvl_len bookkeeping `collect_proof_siblings` reads with, so
+    // a shrinking level never overlaps the level still above it.
+    for &(lvl_start, lvl_len) in &levels[1..] {
+        nodes[lvl_start..lvl_start + lvl_len].clone_from_slice(&parents);
 
-    while parents.len() > 1 {
-        parents = build_upper_level(parents.as_slice(), hasher);
+        if lvl_len > 1 {
+            parents = build_upper_level(parents.as_slice(), hasher);
+        }
+    }
+}
 
-        upper_level_start -= parents.len() - 1;
-        upper_level_end = upper_level_start + parents.len();
-        nodes[upper_level_start..upper_level_end].clone_from_slice(&parents);
+/// Compares two byte slices in constant time with respect to their
+/// contents, to avoid leaking information about where a hash mismatch
+/// occurs through a timing side channel. Slices of different lengths
+/// return `false` at once; slices of equal length are always scanned in
+/// full.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
     }
 
-    nodes[0] = parents.remove(0);
+    let mut diff = 0u8;
+    for i in 0..a.len() {
+        diff |= a[i] ^ b[i];
+    }
+    diff == 0
 }
 
-fn next_power_of_2(n: usize) -> usize {
-    let mut v = n;
-    v -= 1;
-    v |= v >> 1;
-    v |= v >> 2;
-    v |= v >> 4;
-    v |= v >> 8;
-    v |= v >> 16;
-    v += 1;
-    v
+/// Counts the internal nodes above `count_leaves` leaves under the
+/// duplicate-last-if-odd halving rule [`MerkleTree::build`] uses—i.e. the
+/// sum of every level's length from the leaves' parents up to the root.
+/// This is *not* `next_power_of_2(count_leaves) - 1`: that formula assumes a
+/// complete binary tree padded out to the next power of two, which only
+/// coincides with the duplicate-last-if-odd level sizes for some leaf
+/// counts (it overcounts e.g. 5 and 6 leaves, corrupting the root's index).
+///
+/// [`MerkleTree::build`]: struct.MerkleTree.html#method.build
+fn calculate_internal_nodes_count(count_leaves: usize) -> usize {
+    let mut total = 0;
+    let mut lvl_len = count_leaves;
+
+    while lvl_len > 1 {
+        lvl_len = (lvl_len + 1) / 2;
+        total += lvl_len;
+    }
+
+    total
 }
 
-fn calculate_internal_nodes_count(count_leaves: usize) -> usize {
-    next_power_of_2(count_leaves) - 1
+/// Returns the `(lvl_start, lvl_len)` of every level of a breadth-first
+/// `nodes` store, from the leaves (index 0) up to and including the root
+/// (the last entry, always `(0, 1)`).
+fn node_levels(count_internal_nodes: usize, count_leaves: usize) -> Vec<(usize, usize)> {
+    let mut levels = Vec::new();
+    let mut lvl_start = count_internal_nodes;
+    let mut lvl_len = count_leaves;
+
+    loop {
+        levels.push((lvl_start, lvl_len));
+        if lvl_len <= 1 {
+            break;
+        }
+
+        lvl_len = (lvl_len + 1) / 2;
+        lvl_start -= lvl_len;
+    }
+
+    levels
 }
 
-impl<H> MerkleTree<H>
+impl<H, S> MerkleTree<H, S>
+    where S: NodeStore
 {
     /// Constructs a tree from values of data. Data could be anything as long as it could be
     /// represented as bytes array.
@@ -222,7 +438,7 @@ impl<H> MerkleTree<H>
     /// let block = "Hello World";
     /// let _t: MerkleTree = MerkleTree::build(&[block, block]);
     /// ```
-    pub fn build<T>(values: &[T]) -> MerkleTree<H>
+    pub fn build<T>(values: &[T]) -> MerkleTree<H, S>
         where H: Digest + Default, T: AsBytes
     {
         let mut hasher = Default::default();
@@ -246,7 +462,7 @@ impl<H> MerkleTree<H>
     ///     let _t: MT = MT::build_with_hasher(&[block, block], Sha512::new());
     /// }
     /// ```
-    pub fn build_with_hasher<T>(values: &[T], mut hasher: H) -> MerkleTree<H>
+    pub fn build_with_hasher<T>(values: &[T], mut hasher: H) -> MerkleTree<H, S>
         where H: Digest, T: AsBytes
     {
         let count_leaves = values.len();
@@ -265,7 +481,7 @@ impl<H> MerkleTree<H>
         build_internal_nodes(&mut nodes, count_internal_nodes, &mut hasher);
 
         MerkleTree {
-            nodes: nodes,
+            nodes: S::from_nodes(nodes),
             count_internal_nodes: count_internal_nodes,
             count_leaves: count_leaves,
             hasher: hasher,
@@ -284,7 +500,33 @@ impl<H> MerkleTree<H>
     /// assert!(t.root_hash().len() > 0);
     /// ```
     pub fn root_hash(&self) -> Vec<u8> {
-        self.nodes[0].clone()
+        self.nodes.get(0).clone()
+    }
+
+    /// Returns the root hash of the tree as a [`Hash256`], which round-trips
+    /// through [`Hash256::to_hex`]/[`Hash256::from_hex`] (or the base64
+    /// equivalents), letting it be persisted and re-loaded as a trusted
+    /// root. Returns `None` if the hasher in use doesn't produce 32-byte
+    /// hashes.
+    ///
+    /// [`Hash256`]: struct.Hash256.html
+    /// [`Hash256::to_hex`]: struct.Hash256.html#method.to_hex
+    /// [`Hash256::from_hex`]: struct.Hash256.html#method.from_hex
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use merkle_tree::{MerkleTree, Hash256};
+    ///
+    /// let block = "Hello World";
+    /// let t: MerkleTree = MerkleTree::build(&[block, block]);
+    /// let root = t.root_hash256().unwrap();
+    ///
+    /// let saved = root.to_hex();
+    /// assert_eq!(root, Hash256::from_hex(&saved).unwrap());
+    /// ```
+    pub fn root_hash256(&self) -> Option<Hash256> {
+        Hash256::from_bytes(self.nodes.get(0)).ok()
     }
 
     /// Returns root hash of the tree as a string.
@@ -300,7 +542,7 @@ impl<H> MerkleTree<H>
     /// ```
     pub fn root_hash_str(&self) -> String {
         use rustc_serialize::hex::ToHex;
-        self.nodes[0].as_slice().to_hex()
+        self.nodes.get(0).as_slice().to_hex()
     }
 
     /// Verify value by comparing its hash against the one in the tree. `position` must not
@@ -322,104 +564,996 @@ impl<H> MerkleTree<H>
     {
         assert!(position < self.count_leaves, "position does not relate to any leaf");
 
-        self.nodes[self.count_internal_nodes + position].as_slice() ==
-            hash_leaf_node(value, &mut self.hasher).as_slice()
+        constant_time_eq(self.nodes.get(self.count_internal_nodes + position),
+                         &hash_leaf_node(value, &mut self.hasher))
     }
-}
 
-/// The default [`Hasher`] used by [`MerkleTree`].
-pub struct DefaultHasher(Sha256);
+    /// Updates the leaf at `position` in place and rehashes only its
+    /// `O(log n)` root-path, instead of rebuilding the whole tree with
+    /// [`build`]. `position` must not exceed count of leaves and starts at
+    /// 0.
+    ///
+    /// [`build`]: #method.build
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use merkle_tree::MerkleTree;
+    ///
+    /// let block1 = "Hello World";
+    /// let block2 = "Bye, bye";
+    /// let mut t: MerkleTree = MerkleTree::build(&[block1, block2]);
+    /// t.update_leaf(0, &block2);
+    /// assert!(t.verify(0, &block2));
+    /// ```
+    pub fn update_leaf<T>(&mut self, position: usize, value: &T)
+        where H: Digest, T: AsBytes
+    {
+        assert!(position < self.count_leaves, "position does not relate to any leaf");
 
-impl DefaultHasher {
-    /// Creates a new `DefaultHasher`.
-    pub fn new() -> DefaultHasher {
-        DefaultHasher(Sha256::new())
+        let mut lvl_start = self.count_internal_nodes;
+        let mut lvl_len = self.count_leaves;
+        let mut lvl_i = position;
+
+        self.nodes.set(lvl_start + lvl_i, hash_leaf_node(value, &mut self.hasher));
+
+        while lvl_len > 1 {
+            let parent_lvl_i = lvl_i / 2;
+            let left_lvl_i = parent_lvl_i * 2;
+            let right_lvl_i = left_lvl_i + 1;
+
+            let left = self.nodes.get(lvl_start + left_lvl_i).clone();
+            let new_parent = if right_lvl_i < lvl_len {
+                let right = self.nodes.get(lvl_start + right_lvl_i).clone();
+                hash_internal_node(&left, &right, &mut self.hasher)
+            } else {
+                hash_internal_node_with_one_child(&left, &mut self.hasher)
+            };
+
+            let parent_lvl_len = (lvl_len + 1) / 2;
+            let parent_lvl_start = lvl_start - parent_lvl_len;
+            self.nodes.set(parent_lvl_start + parent_lvl_i, new_parent);
+
+            lvl_i = parent_lvl_i;
+            lvl_len = parent_lvl_len;
+            lvl_start = parent_lvl_start;
+        }
     }
-}
 
-/// Implementation of the Default trait from std library
-impl Default for DefaultHasher {
-    /// Creates a new `DefaultHasher` using [`DefaultHasher::new`]. See
-    /// [`DefaultHasher::new`] documentation for more information.
+    /// Builds an inclusion proof for the leaf at `index`, allowing a verifier
+    /// who only has the root hash to check a single value without holding the
+    /// whole tree. Returns `None` if `index` does not relate to any leaf.
     ///
-    /// [`DefaultHasher::new`]: #method.new
-    fn default() -> DefaultHasher {
-        DefaultHasher::new()
-    }
-}
+    /// # Examples
+    ///
+    /// ```
+    /// use merkle_tree::MerkleTree;
+    ///
+    /// let block1 = "Hello World";
+    /// let block2 = "Bye, bye";
+    /// let t: MerkleTree = MerkleTree::build(&[block1, block2]);
+    /// let proof = t.proof(0).unwrap();
+    /// assert!(proof.verify(&block1, merkle_tree::DefaultHasher::new()));
+    /// ```
+    pub fn proof(&self, index: usize) -> Option<Proof> {
+        if index >= self.count_leaves {
+            return None;
+        }
 
-/// Implementation of the Digest trait from crypto library for our DefaultHasher
-impl Digest for DefaultHasher {
-    #[inline]
-    fn input(&mut self, d: &[u8]) {
-        self.0.input(d)
+        Some(Proof {
+            index: index,
+            siblings: collect_proof_siblings(&self.nodes,
+                                              self.count_internal_nodes,
+                                              self.count_leaves,
+                                              index),
+            root_hash: self.root_hash(),
+        })
     }
 
-    #[inline]
-    fn result(&mut self, out: &mut [u8]) {
-        self.0.result(out)
+    /// Builds a compressed inclusion proof for several leaves at once. Unlike
+    /// concatenating one [`Proof`] per index, shared internal nodes are
+    /// authenticated only once, keeping the proof size between `h -
+    /// log2(k)` and `k(h - log2(k))` hashes for `k` leaves and a tree of
+    /// height `h`, instead of `k` independent paths.
+    ///
+    /// Out-of-range indices are ignored.
+    ///
+    /// [`Proof`]: struct.Proof.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use merkle_tree::MerkleTree;
+    ///
+    /// let blocks = ["a", "b", "c", "d"];
+    /// let t: MerkleTree = MerkleTree::build(&blocks);
+    /// let proof = t.batch_proof(&[0, 2]);
+    /// assert!(proof.verify(&["a", "c"], merkle_tree::DefaultHasher::new()));
+    /// ```
+    pub fn batch_proof(&self, indices: &[usize]) -> BatchProof {
+        let mut known: Vec<usize> = indices.iter().cloned()
+            .filter(|&i| i < self.count_leaves)
+            .collect();
+        known.sort();
+        known.dedup();
+
+        let orig_indices = known.clone();
+
+        let mut lvl_start = self.count_internal_nodes;
+        let mut lvl_len = self.count_leaves;
+        let mut aux = Vec::new();
+
+        while lvl_len > 1 {
+            let known_set: HashSet<usize> = known.iter().cloned().collect();
+            let mut next_set: HashSet<usize> = HashSet::new();
+
+            for &i in &known {
+                let sibling_i = i ^ 1;
+                let parent = i / 2;
+
+                if !known_set.contains(&sibling_i) {
+                    let sibling = if sibling_i < lvl_len {
+                        self.nodes.get(lvl_start + sibling_i).clone()
+                    } else {
+                        self.nodes.get(lvl_start + i).clone()
+                    };
+                    aux.push(sibling);
+                }
+
+                next_set.insert(parent);
+            }
+
+            known = next_set.into_iter().collect();
+            known.sort();
+
+            lvl_len = (lvl_len + 1) / 2;
+            lvl_start -= lvl_len;
+        }
+
+        BatchProof {
+            indices: orig_indices,
+            aux: aux,
+            count_leaves: self.count_leaves,
+            root_hash: self.root_hash(),
+        }
     }
 
-    #[inline]
-    fn reset(&mut self) {
-        self.0.reset()
+    /// Drops every internal node not required by `pruner`'s outstanding
+    /// proofs/witnesses from this tree's node store, reclaiming the space
+    /// they held. Leaves and the root are never dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use merkle_tree::{MerkleTree, MerkleTreePruner};
+    ///
+    /// let blocks = ["a", "b", "c", "d"];
+    /// let mut t: MerkleTree = MerkleTree::build(&blocks);
+    ///
+    /// let mut pruner = MerkleTreePruner::new(3, 4);
+    /// pruner.keep_leaf(0);
+    /// t.prune(&pruner);
+    ///
+    /// assert!(t.proof(0).is_some());
+    /// ```
+    pub fn prune(&mut self, pruner: &MerkleTreePruner) {
+        pruner.prune(&mut self.nodes);
     }
 
-    #[inline]
-    fn output_bits(&self) -> usize {
-        self.0.output_bits()
+    /// Builds a compact, serializable proof of several leaves at once,
+    /// merkleblock-style: unmatched subtrees are summarized by a single
+    /// hash instead of being walked down to their leaves. Unlike
+    /// [`batch_proof`], the result ([`PartialTree`]) holds no root hash—
+    /// [`PartialTree::extract_root_and_matches`] recomputes the root
+    /// itself from the matched leaves and the summary hashes.
+    ///
+    /// Out-of-range indices are ignored.
+    ///
+    /// [`batch_proof`]: #method.batch_proof
+    /// [`PartialTree`]: struct.PartialTree.html
+    /// [`PartialTree::extract_root_and_matches`]: struct.PartialTree.html#method.extract_root_and_matches
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use merkle_tree::MerkleTree;
+    ///
+    /// let blocks = ["a", "b", "c", "d"];
+    /// let t: MerkleTree = MerkleTree::build(&blocks);
+    /// let partial = t.partial(&[0, 2]);
+    ///
+    /// let (root, matches) = partial.extract_root_and_matches(merkle_tree::DefaultHasher::new()).unwrap();
+    /// assert_eq!(root, t.root_hash());
+    /// assert_eq!(matches.len(), 2);
+    /// ```
+    pub fn partial(&self, matched: &[usize]) -> PartialTree {
+        let levels = node_levels(self.count_internal_nodes, self.count_leaves);
+
+        let matched_set: HashSet<usize> = matched.iter().cloned()
+            .filter(|&i| i < self.count_leaves)
+            .collect();
+
+        let leaf_row: Vec<bool> = (0..levels[0].1).map(|i| matched_set.contains(&i)).collect();
+        let mut matched_at = vec![leaf_row];
+        for height in 1..levels.len() {
+            let lower_len = levels[height - 1].1;
+            let mut row = vec![false; levels[height].1];
+            for pos in 0..levels[height].1 {
+                let left = matched_at[height - 1][pos * 2];
+                let right = if pos * 2 + 1 < lower_len {
+                    matched_at[height - 1][pos * 2 + 1]
+                } else {
+                    left
+                };
+                row[pos] = left || right;
+            }
+            matched_at.push(row);
+        }
+
+        let mut flags = Vec::new();
+        let mut hashes = Vec::new();
+        self.traverse_partial(&levels, &matched_at, levels.len() - 1, 0, &mut flags, &mut hashes);
+
+        PartialTree {
+            count_leaves: self.count_leaves,
+            flags: flags,
+            hashes: hashes,
+        }
     }
 
-    #[inline]
-    fn block_size(&self) -> usize {
-        self.0.block_size()
+    fn traverse_partial(&self,
+                         levels: &[(usize, usize)],
+                         matched_at: &[Vec<bool>],
+                         height: usize,
+                         pos: usize,
+                         flags: &mut Vec<bool>,
+                         hashes: &mut Vec<Hash>) {
+        let is_match = matched_at[height][pos];
+        flags.push(is_match);
+
+        if height == 0 || !is_match {
+            let lvl_start = levels[height].0;
+            hashes.push(self.nodes.get(lvl_start + pos).clone());
+            return;
+        }
+
+        let lower_len = levels[height - 1].1;
+        self.traverse_partial(levels, matched_at, height - 1, pos * 2, flags, hashes);
+        if pos * 2 + 1 < lower_len {
+            self.traverse_partial(levels, matched_at, height - 1, pos * 2 + 1, flags, hashes);
+        }
     }
 }
 
-pub trait AsBytes {
-    fn as_bytes(&self) -> &[u8];
+/// An inclusion proof for a single leaf. Lets a verifier who only has the
+/// trusted root hash check that a value was part of the tree that produced
+/// it, without needing the rest of the tree.
+#[derive(Debug, Clone)]
+pub struct Proof {
+    index: usize,
+    siblings: Vec<Hash>,
+    root_hash: Hash,
 }
 
-impl<'a> AsBytes for &'a str {
-    fn as_bytes(&self) -> &[u8] {
-        str::as_bytes(&self)
-    }
-}
+impl Proof {
+    /// Recomputes the leaf hash for `value` and folds the sibling hashes of
+    /// this proof up to the root, using `hasher`, then checks the result
+    /// against the root hash captured when the proof was built.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use merkle_tree::MerkleTree;
+    ///
+    /// let block1 = "Hello World";
+    /// let block2 = "Bye, bye";
+    /// let t: MerkleTree = MerkleTree::build(&[block1, block2]);
+    /// let proof = t.proof(1).unwrap();
+    /// assert!(proof.verify(&block2, merkle_tree::DefaultHasher::new()));
+    /// assert!(!proof.verify(&block1, merkle_tree::DefaultHasher::new()));
+    /// ```
+    pub fn verify<T, H>(&self, value: &T, mut hasher: H) -> bool
+        where T: AsBytes, H: Digest
+    {
+        let mut hash = hash_leaf_node(value, &mut hasher);
+        let mut i = self.index;
 
-impl AsBytes for String {
-    fn as_bytes(&self) -> &[u8] {
-        String::as_bytes(&self)
+        for sibling in &self.siblings {
+            hash = combine_sibling(i, &hash, sibling, &mut hasher);
+            i /= 2;
+        }
+
+        constant_time_eq(&hash, &self.root_hash)
     }
 }
 
-impl<'a> AsBytes for &'a [u8] {
-    fn as_bytes(&self) -> &[u8] {
-        *self
-    }
+/// A compressed inclusion proof for several leaves, built by
+/// [`MerkleTree::batch_proof`]. Internal nodes shared by more than one of
+/// the requested leaves are authenticated only once, instead of repeating
+/// them in one independent [`Proof`] per leaf.
+///
+/// [`MerkleTree::batch_proof`]: struct.MerkleTree.html#method.batch_proof
+/// [`Proof`]: struct.Proof.html
+#[derive(Debug, Clone)]
+pub struct BatchProof {
+    indices: Vec<usize>,
+    aux: Vec<Hash>,
+    count_leaves: usize,
+    root_hash: Hash,
 }
 
-#[cfg(test)]
-mod test_tree {
-    use super::MerkleTree;
-    use super::crypto::sha2::Sha256;
+impl BatchProof {
+    /// Checks `values` against this proof. `values` must be given in the
+    /// same order as the sorted, deduplicated indices that were passed to
+    /// [`MerkleTree::batch_proof`], one value per index.
+    ///
+    /// [`MerkleTree::batch_proof`]: struct.MerkleTree.html#method.batch_proof
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use merkle_tree::MerkleTree;
+    ///
+    /// let blocks = ["a", "b", "c", "d"];
+    /// let t: MerkleTree = MerkleTree::build(&blocks);
+    /// let proof = t.batch_proof(&[3, 1]);
+    /// assert!(proof.verify(&["b", "d"], merkle_tree::DefaultHasher::new()));
+    /// ```
+    pub fn verify<T, H>(&self, values: &[T], mut hasher: H) -> bool
+        where T: AsBytes, H: Digest
+    {
+        if values.len() != self.indices.len() {
+            return false;
+        }
 
-    #[test]
-    #[should_panic]
-    fn test_0_values() {
-        let _t: MerkleTree = MerkleTree::build::<String>(&[]);
-    }
+        if self.indices.is_empty() {
+            return true;
+        }
 
-    #[test]
-    fn test_odd_number_of_values() {
-        let block = "Hello World";
-        let _t: MerkleTree = MerkleTree::build(&[block, block, block]);
-    }
+        let mut known: HashMap<usize, Hash> = self.indices.iter().cloned()
+            .zip(values.iter().map(|v| hash_leaf_node(v, &mut hasher)))
+            .collect();
 
-    #[test]
-    fn test_even_number_of_values() {
-        let block = "Hello World";
-        let _t: MerkleTree = MerkleTree::build(&[block, block, block, block]);
-    }
+        let mut lvl_len = self.count_leaves;
+        let mut aux_iter = self.aux.iter();
+
+        while lvl_len > 1 {
+            let mut known_keys: Vec<usize> = known.keys().cloned().collect();
+            known_keys.sort();
+
+            let mut next: HashMap<usize, Hash> = HashMap::new();
+
+            for &i in &known_keys {
+                let parent = i / 2;
+                if next.contains_key(&parent) {
+                    continue;
+                }
+
+                let mine = &known[&i];
+                let sibling_i = i ^ 1;
+                let hash = if let Some(sibling) = known.get(&sibling_i) {
+                    combine_sibling(i, mine, sibling, &mut hasher)
+                } else {
+                    match aux_iter.next() {
+                        Some(aux_hash) => combine_sibling(i, mine, aux_hash, &mut hasher),
+                        None => return false,
+                    }
+                };
+
+                next.insert(parent, hash);
+            }
+
+            known = next;
+            lvl_len = (lvl_len + 1) / 2;
+        }
+
+        match known.get(&0) {
+            Some(hash) => constant_time_eq(hash, &self.root_hash),
+            None => false,
+        }
+    }
+}
+
+/// A compact, serializable proof of several leaves' inclusion in a
+/// [`MerkleTree`], built by [`MerkleTree::partial`]: a depth-first
+/// traversal of the tree recorded as one flag per visited node (whether it
+/// was descended into because it, or a descendant, matched) followed by
+/// the hash of every node where the traversal stopped instead of
+/// descending further. Unlike [`Proof`]/[`BatchProof`], which store the
+/// root hash captured when they were built, a `PartialTree` stores no
+/// root: [`extract_root_and_matches`](#method.extract_root_and_matches)
+/// recomputes it, so it can be handed to a verifier who never held the
+/// original tree.
+///
+/// [`MerkleTree`]: struct.MerkleTree.html
+/// [`MerkleTree::partial`]: struct.MerkleTree.html#method.partial
+/// [`Proof`]: struct.Proof.html
+/// [`BatchProof`]: struct.BatchProof.html
+#[derive(Debug, Clone)]
+pub struct PartialTree {
+    count_leaves: usize,
+    flags: Vec<bool>,
+    hashes: Vec<Hash>,
+}
+
+/// Why decoding/replaying a [`PartialTree`] failed.
+///
+/// [`PartialTree`]: struct.PartialTree.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartialTreeError {
+    /// [`PartialTree::from_bytes`] ran out of input before it had read
+    /// every field its own length prefixes promised, or was left with
+    /// unread trailing bytes—the wire data was truncated or tampered with.
+    ///
+    /// [`PartialTree::from_bytes`]: struct.PartialTree.html#method.from_bytes
+    Malformed,
+    /// [`PartialTree::extract_root_and_matches`] ran out of recorded
+    /// `flags`/`hashes` before the traversal it was replaying finished—the
+    /// two don't actually describe a complete depth-first walk, which can
+    /// happen even for well-formed wire data if it was truncated or
+    /// tampered with after a valid decode.
+    ///
+    /// [`PartialTree::extract_root_and_matches`]: struct.PartialTree.html#method.extract_root_and_matches
+    Truncated,
+}
+
+impl fmt::Display for PartialTreeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PartialTreeError::Malformed => write!(f, "partial tree bytes are malformed"),
+            PartialTreeError::Truncated => write!(f, "partial tree data is truncated"),
+        }
+    }
+}
+
+fn write_u64(out: &mut Vec<u8>, n: u64) {
+    out.extend_from_slice(&n.to_be_bytes());
+}
+
+fn read_u64(bytes: &[u8], cursor: &mut usize) -> Result<u64, PartialTreeError> {
+    let end = cursor.checked_add(8).ok_or(PartialTreeError::Malformed)?;
+    let field = bytes.get(*cursor..end).ok_or(PartialTreeError::Malformed)?;
+
+    let mut buf = [0u8; 8];
+    buf.clone_from_slice(field);
+    *cursor = end;
+    Ok(u64::from_be_bytes(buf))
+}
+
+impl PartialTree {
+    /// Encodes this `PartialTree` as bytes suitable for sending to a
+    /// verifier in a different process—one who never held the original
+    /// tree—to be parsed back with [`from_bytes`].
+    ///
+    /// [`from_bytes`]: #method.from_bytes
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use merkle_tree::{MerkleTree, PartialTree};
+    ///
+    /// let blocks = ["a", "b", "c", "d"];
+    /// let t: MerkleTree = MerkleTree::build(&blocks);
+    /// let partial = t.partial(&[0, 2]);
+    ///
+    /// let wire = partial.to_bytes();
+    /// let roundtripped = PartialTree::from_bytes(&wire).unwrap();
+    ///
+    /// let (root, matches) = roundtripped.extract_root_and_matches(merkle_tree::DefaultHasher::new()).unwrap();
+    /// assert_eq!(root, t.root_hash());
+    /// assert_eq!(matches.len(), 2);
+    /// ```
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+
+        write_u64(&mut out, self.count_leaves as u64);
+
+        write_u64(&mut out, self.flags.len() as u64);
+        out.extend(self.flags.iter().map(|&f| if f { 1u8 } else { 0u8 }));
+
+        let hash_len = self.hashes.first().map_or(0, |h| h.len());
+        write_u64(&mut out, self.hashes.len() as u64);
+        write_u64(&mut out, hash_len as u64);
+        for hash in &self.hashes {
+            out.extend_from_slice(hash);
+        }
+
+        out
+    }
+
+    /// Parses a `PartialTree` back from bytes produced by [`to_bytes`].
+    /// Returns [`PartialTreeError::Malformed`] if `bytes` is truncated,
+    /// has trailing data, or its length prefixes don't add up—never
+    /// panics on untrusted input.
+    ///
+    /// [`to_bytes`]: #method.to_bytes
+    /// [`PartialTreeError::Malformed`]: enum.PartialTreeError.html#variant.Malformed
+    pub fn from_bytes(bytes: &[u8]) -> Result<PartialTree, PartialTreeError> {
+        let mut cursor = 0;
+
+        let count_leaves = read_u64(bytes, &mut cursor)? as usize;
+
+        let num_flags = read_u64(bytes, &mut cursor)? as usize;
+        let flags_end = cursor.checked_add(num_flags).ok_or(PartialTreeError::Malformed)?;
+        let flags: Vec<bool> = bytes.get(cursor..flags_end)
+            .ok_or(PartialTreeError::Malformed)?
+            .iter()
+            .map(|&b| b != 0)
+            .collect();
+        cursor = flags_end;
+
+        let num_hashes = read_u64(bytes, &mut cursor)? as usize;
+        let hash_len = read_u64(bytes, &mut cursor)? as usize;
+
+        // to_bytes only ever writes hash_len=0 alongside num_hashes=0 (an
+        // empty PartialTree has no hashes to measure the length of), so a
+        // nonzero num_hashes paired with hash_len=0 is already malformed—
+        // and rejecting it here avoids a huge attacker-chosen num_hashes
+        // sailing through the total-length check below as "0 bytes".
+        if hash_len == 0 && num_hashes != 0 {
+            return Err(PartialTreeError::Malformed);
+        }
+
+        // Validate the total hash payload length up front, against the
+        // actual remaining bytes, before trusting num_hashes/hash_len for
+        // anything further: both are still attacker-controlled here, and
+        // without this check a bogus huge num_hashes would pass every
+        // individual per-hash bounds check (each slice still fits) while
+        // the decode loop ran for an enormous number of iterations.
+        let hashes_len = num_hashes.checked_mul(hash_len).ok_or(PartialTreeError::Malformed)?;
+        let hashes_end = cursor.checked_add(hashes_len).ok_or(PartialTreeError::Malformed)?;
+        if hashes_end > bytes.len() {
+            return Err(PartialTreeError::Malformed);
+        }
+
+        let mut hashes = Vec::with_capacity(num_hashes);
+        for _ in 0..num_hashes {
+            let hash_end = cursor + hash_len;
+            hashes.push(bytes[cursor..hash_end].to_vec());
+            cursor = hash_end;
+        }
+
+        if cursor != bytes.len() {
+            return Err(PartialTreeError::Malformed);
+        }
+
+        Ok(PartialTree {
+            count_leaves: count_leaves,
+            flags: flags,
+            hashes: hashes,
+        })
+    }
+
+    /// Replays the depth-first traversal recorded by
+    /// [`MerkleTree::partial`], recomputing the root hash with `hasher`
+    /// (which must match the one the originating tree used) and collecting
+    /// every leaf the traversal confirms as matched, paired with its hash.
+    /// Returns [`PartialTreeError::Truncated`] rather than panicking if
+    /// `flags`/`hashes` run out before the traversal they're replaying
+    /// does—the shape a tampered-with or truncated-in-transit
+    /// `PartialTree` would have.
+    ///
+    /// [`MerkleTree::partial`]: struct.MerkleTree.html#method.partial
+    /// [`PartialTreeError::Truncated`]: enum.PartialTreeError.html#variant.Truncated
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use merkle_tree::MerkleTree;
+    ///
+    /// let blocks = ["a", "b", "c", "d"];
+    /// let t: MerkleTree = MerkleTree::build(&blocks);
+    /// let partial = t.partial(&[0, 2]);
+    ///
+    /// let (root, matches) = partial.extract_root_and_matches(merkle_tree::DefaultHasher::new()).unwrap();
+    /// assert_eq!(root, t.root_hash());
+    /// assert_eq!(matches.len(), 2);
+    /// assert!(matches.iter().any(|&(index, _)| index == 0));
+    /// assert!(matches.iter().any(|&(index, _)| index == 2));
+    /// ```
+    pub fn extract_root_and_matches<H>(&self,
+                                        mut hasher: H)
+                                        -> Result<(Hash, Vec<(usize, Hash)>), PartialTreeError>
+        where H: Digest
+    {
+        let count_internal_nodes = calculate_internal_nodes_count(self.count_leaves);
+        let levels = node_levels(count_internal_nodes, self.count_leaves);
+
+        let mut flag_i = 0;
+        let mut hash_i = 0;
+        let mut matches = Vec::new();
+
+        let root = rebuild_partial(&levels,
+                                    levels.len() - 1,
+                                    0,
+                                    &self.flags,
+                                    &mut flag_i,
+                                    &self.hashes,
+                                    &mut hash_i,
+                                    &mut matches,
+                                    &mut hasher)?;
+
+        Ok((root, matches))
+    }
+}
+
+fn rebuild_partial<H>(levels: &[(usize, usize)],
+                       height: usize,
+                       pos: usize,
+                       flags: &[bool],
+                       flag_i: &mut usize,
+                       hashes: &[Hash],
+                       hash_i: &mut usize,
+                       matches: &mut Vec<(usize, Hash)>,
+                       hasher: &mut H)
+                       -> Result<Hash, PartialTreeError>
+    where H: Digest
+{
+    let is_match = *flags.get(*flag_i).ok_or(PartialTreeError::Truncated)?;
+    *flag_i += 1;
+
+    if height == 0 || !is_match {
+        let hash = hashes.get(*hash_i).ok_or(PartialTreeError::Truncated)?.clone();
+        *hash_i += 1;
+
+        if height == 0 && is_match {
+            matches.push((pos, hash.clone()));
+        }
+
+        return Ok(hash);
+    }
+
+    let lower_len = levels[height - 1].1;
+    let left = rebuild_partial(levels, height - 1, pos * 2, flags, flag_i, hashes, hash_i, matches, hasher)?;
+
+    if pos * 2 + 1 < lower_len {
+        let right = rebuild_partial(levels, height - 1, pos * 2 + 1, flags, flag_i, hashes, hash_i, matches, hasher)?;
+        Ok(hash_internal_node(&left, &right, hasher))
+    } else {
+        Ok(hash_internal_node_with_one_child(&left, hasher))
+    }
+}
+
+/// The reason parsing a [`Hash256`] from a hex or base64 string failed.
+///
+/// [`Hash256`]: struct.Hash256.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    /// The input was validly encoded, but didn't decode to 32 bytes.
+    InvalidLength,
+    /// The input isn't valid hex/base64: it contains characters outside
+    /// the alphabet, or its length isn't valid for the encoding (e.g. an
+    /// odd number of hex digits).
+    InvalidEncoding,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::InvalidLength => write!(f, "decoded data is not 32 bytes long"),
+            ParseError::InvalidEncoding => write!(f, "input is not valid hex/base64"),
+        }
+    }
+}
+
+/// A fixed-size, 32-byte hash. Unlike the bare byte vectors [`root_hash`]
+/// returns, a `Hash256` can be parsed back from a saved hex or base64
+/// string, which lets a trusted root be persisted and safely re-loaded
+/// later.
+///
+/// [`root_hash`]: struct.MerkleTree.html#method.root_hash
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hash256([u8; 32]);
+
+impl Hash256 {
+    fn from_bytes(bytes: &[u8]) -> Result<Hash256, ParseError> {
+        if bytes.len() != 32 {
+            return Err(ParseError::InvalidLength);
+        }
+
+        let mut out = [0u8; 32];
+        out.clone_from_slice(bytes);
+        Ok(Hash256(out))
+    }
+
+    /// Parses a `Hash256` from a hex string.
+    pub fn from_hex(s: &str) -> Result<Hash256, ParseError> {
+        use rustc_serialize::hex::FromHex;
+
+        let bytes = s.from_hex().map_err(|_| ParseError::InvalidEncoding)?;
+        Hash256::from_bytes(&bytes)
+    }
+
+    /// Parses a `Hash256` from a standard-alphabet base64 string.
+    pub fn from_base64(s: &str) -> Result<Hash256, ParseError> {
+        use rustc_serialize::base64::FromBase64;
+
+        let bytes = s.from_base64().map_err(|_| ParseError::InvalidEncoding)?;
+        Hash256::from_bytes(&bytes)
+    }
+
+    /// Encodes this hash as a hex string.
+    pub fn to_hex(&self) -> String {
+        use rustc_serialize::hex::ToHex;
+        self.0.to_hex()
+    }
+
+    /// Encodes this hash as a standard-alphabet base64 string.
+    pub fn to_base64(&self) -> String {
+        use rustc_serialize::base64::{ToBase64, STANDARD};
+        self.0.to_base64(STANDARD)
+    }
+
+    /// Returns the raw 32 bytes of this hash.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// An append-only Merkle tree that supports streaming construction over
+/// data that arrives one block at a time, as in an append-only commitment
+/// log. Unlike [`MerkleTree`], which needs every value up front to
+/// [`build`], `IncrementalTree` only updates the "frontier"—at most
+/// `height` rightmost, not-yet-paired subtree roots—so [`append`]
+/// costs `O(log n)` instead of a full rebuild. It also keeps the hash of
+/// every appended leaf, from which [`witness`] rebuilds the whole tree.
+///
+/// [`MerkleTree`]: struct.MerkleTree.html
+/// [`build`]: struct.MerkleTree.html#method.build
+/// [`append`]: #method.append
+/// [`witness`]: #method.witness
+pub struct IncrementalTree<H = DefaultHasher> {
+    hasher: H,
+    frontier: Vec<Option<Hash>>,
+    leaves: Vec<Hash>,
+}
+
+impl<H> IncrementalTree<H> {
+    /// Creates an empty `IncrementalTree`, using a default-constructed
+    /// hasher.
+    pub fn new() -> IncrementalTree<H>
+        where H: Default
+    {
+        IncrementalTree::with_hasher(Default::default())
+    }
+
+    /// Creates an empty `IncrementalTree` using the given hasher.
+    pub fn with_hasher(hasher: H) -> IncrementalTree<H> {
+        IncrementalTree {
+            hasher: hasher,
+            frontier: Vec::new(),
+            leaves: Vec::new(),
+        }
+    }
+
+    /// Appends a new leaf, updating the frontier without touching the rest
+    /// of the tree.
+    pub fn append<T>(&mut self, value: &T)
+        where H: Digest, T: AsBytes
+    {
+        let mut node = hash_leaf_node(value, &mut self.hasher);
+        self.leaves.push(node.clone());
+
+        let mut level = 0;
+        loop {
+            if level == self.frontier.len() {
+                self.frontier.push(None);
+            }
+
+            match self.frontier[level].take() {
+                Some(left) => {
+                    node = hash_internal_node(&left, &node, &mut self.hasher);
+                    level += 1;
+                }
+                None => {
+                    self.frontier[level] = Some(node);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Returns the number of leaves appended so far.
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// Folds the frontier into the current root hash, duplicating any
+    /// not-yet-paired node at an empty right position—matching the
+    /// duplication rule [`MerkleTree::build`] uses for an odd number of
+    /// nodes at a level. Returns `None` if nothing has been appended yet.
+    ///
+    /// [`MerkleTree::build`]: struct.MerkleTree.html#method.build
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use merkle_tree::IncrementalTree;
+    ///
+    /// let mut t: IncrementalTree = IncrementalTree::new();
+    /// t.append(&"a");
+    /// t.append(&"b");
+    /// t.append(&"c");
+    /// assert!(t.root().is_some());
+    /// ```
+    pub fn root(&mut self) -> Option<Hash>
+        where H: Digest
+    {
+        if self.leaves.is_empty() {
+            return None;
+        }
+
+        let mut carry: Option<Hash> = None;
+        let last_level = self.frontier.len() - 1;
+
+        for level in 0..self.frontier.len() {
+            carry = match (self.frontier[level].clone(), carry) {
+                // A lone value at the very top of the frontier is already the
+                // finished root—there's no level above it to pair with—so
+                // only duplicate-hash it up when another frontier level
+                // still remains to combine it with.
+                (Some(node), None) if level == last_level => Some(node),
+                (None, Some(carried)) if level == last_level => Some(carried),
+                (Some(node), None) =>
+                    Some(hash_internal_node_with_one_child(&node, &mut self.hasher)),
+                (None, Some(carried)) =>
+                    Some(hash_internal_node_with_one_child(&carried, &mut self.hasher)),
+                (Some(node), Some(carried)) =>
+                    Some(hash_internal_node(&node, &carried, &mut self.hasher)),
+                (None, None) => None,
+            };
+        }
+
+        carry
+    }
+
+    /// Builds an inclusion proof for the leaf at `index`, for the tree as it
+    /// stands now. Returns `None` if `index` does not relate to any
+    /// previously appended leaf.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use merkle_tree::IncrementalTree;
+    ///
+    /// let mut t: IncrementalTree = IncrementalTree::new();
+    /// t.append(&"a");
+    /// t.append(&"b");
+    /// t.append(&"c");
+    ///
+    /// let witness = t.witness(1).unwrap();
+    /// assert!(witness.verify(&"b", merkle_tree::DefaultHasher::new()));
+    /// ```
+    pub fn witness(&mut self, index: usize) -> Option<Proof>
+        where H: Digest
+    {
+        let count_leaves = self.leaves.len();
+        if index >= count_leaves {
+            return None;
+        }
+
+        if count_leaves == 1 {
+            // A single-leaf tree has no internal nodes at all—its root *is*
+            // the leaf hash, unwrapped—matching what root() returns for
+            // the same case (calculate_internal_nodes_count and
+            // collect_proof_siblings both no-op when there's only one
+            // leaf), so there's nothing to fold here.
+            let leaf = self.leaves[0].clone();
+            return Some(Proof {
+                index: 0,
+                siblings: Vec::new(),
+                root_hash: leaf,
+            });
+        }
+
+        let count_internal_nodes = calculate_internal_nodes_count(count_leaves);
+        let mut nodes = vec![Vec::new(); count_internal_nodes + count_leaves];
+        nodes[count_internal_nodes..].clone_from_slice(&self.leaves);
+        build_internal_nodes(&mut nodes, count_internal_nodes, &mut self.hasher);
+
+        let nodes = InMemoryNodeStore::from_nodes(nodes);
+        Some(Proof {
+            index: index,
+            siblings: collect_proof_siblings(&nodes, count_internal_nodes, count_leaves, index),
+            root_hash: nodes.get(0).clone(),
+        })
+    }
+}
+
+/// The default [`Hasher`] used by [`MerkleTree`].
+pub struct DefaultHasher(Sha256);
+
+impl DefaultHasher {
+    /// Creates a new `DefaultHasher`.
+    pub fn new() -> DefaultHasher {
+        DefaultHasher(Sha256::new())
+    }
+}
+
+/// Implementation of the Default trait from std library
+impl Default for DefaultHasher {
+    /// Creates a new `DefaultHasher` using [`DefaultHasher::new`]. See
+    /// [`DefaultHasher::new`] documentation for more information.
+    ///
+    /// [`DefaultHasher::new`]: #method.new
+    fn default() -> DefaultHasher {
+        DefaultHasher::new()
+    }
+}
+
+/// Implementation of the Digest trait from crypto library for our DefaultHasher
+impl Digest for DefaultHasher {
+    #[inline]
+    fn input(&mut self, d: &[u8]) {
+        self.0.input(d)
+    }
+
+    #[inline]
+    fn result(&mut self, out: &mut [u8]) {
+        self.0.result(out)
+    }
+
+    #[inline]
+    fn reset(&mut self) {
+        self.0.reset()
+    }
+
+    #[inline]
+    fn output_bits(&self) -> usize {
+        self.0.output_bits()
+    }
+
+    #[inline]
+    fn block_size(&self) -> usize {
+        self.0.block_size()
+    }
+}
+
+pub trait AsBytes {
+    fn as_bytes(&self) -> &[u8];
+}
+
+impl<'a> AsBytes for &'a str {
+    fn as_bytes(&self) -> &[u8] {
+        str::as_bytes(&self)
+    }
+}
+
+impl AsBytes for String {
+    fn as_bytes(&self) -> &[u8] {
+        String::as_bytes(&self)
+    }
+}
+
+impl<'a> AsBytes for &'a [u8] {
+    fn as_bytes(&self) -> &[u8] {
+        *self
+    }
+}
+
+#[cfg(test)]
+mod test_tree {
+    use super::MerkleTree;
+    use super::IncrementalTree;
+    use super::crypto::sha2::Sha256;
+
+    #[test]
+    #[should_panic]
+    fn test_0_values() {
+        let _t: MerkleTree = MerkleTree::build::<String>(&[]);
+    }
+
+    #[test]
+    fn test_odd_number_of_values() {
+        let block = "Hello World";
+        let _t: MerkleTree = MerkleTree::build(&[block, block, block]);
+    }
+
+    #[test]
+    fn test_even_number_of_values() {
+        let block = "Hello World";
+        let _t: MerkleTree = MerkleTree::build(&[block, block, block, block]);
+    }
 
     #[test]
     fn test_hash_stays_the_same_if_data_hasnt_been_changed() {
@@ -428,4 +1562,338 @@ mod test_tree {
         // root hash should stay the same if data hasn't been changed
         assert_eq!("c9978dc3e2d729207ca4c012de993423f19e7bf02161f7f95cdbf28d1b57b88a", t.root_hash_str());
     }
+
+    #[test]
+    fn test_proof_of_nonexistent_leaf_is_none() {
+        let block = "Hello World";
+        let t: MerkleTree = MerkleTree::build(&[block, block]);
+        assert!(t.proof(2).is_none());
+    }
+
+    #[test]
+    fn test_proof_verifies_every_leaf() {
+        let blocks = ["a", "b", "c", "d"];
+        let t: MerkleTree = MerkleTree::build(&blocks);
+
+        for (i, block) in blocks.iter().enumerate() {
+            let proof = t.proof(i).unwrap();
+            assert!(proof.verify(block, super::DefaultHasher::new()));
+        }
+    }
+
+    #[test]
+    fn test_proof_fails_for_wrong_value() {
+        let blocks = ["a", "b", "c", "d"];
+        let t: MerkleTree = MerkleTree::build(&blocks);
+
+        let proof = t.proof(0).unwrap();
+        assert!(!proof.verify(&"not a", super::DefaultHasher::new()));
+    }
+
+    #[test]
+    fn test_batch_proof_verifies_matched_leaves() {
+        let blocks = ["a", "b", "c", "d"];
+        let t: MerkleTree = MerkleTree::build(&blocks);
+
+        let proof = t.batch_proof(&[2, 0]);
+        assert!(proof.verify(&["a", "c"], super::DefaultHasher::new()));
+    }
+
+    #[test]
+    fn test_batch_proof_verifies_single_leaf_subset_of_five() {
+        let blocks = ["a", "b", "c", "d", "e"];
+        let t: MerkleTree = MerkleTree::build(&blocks);
+
+        let proof = t.batch_proof(&[0]);
+        assert!(proof.verify(&["a"], super::DefaultHasher::new()));
+    }
+
+    #[test]
+    fn test_batch_proof_verifies_all_leaves() {
+        let blocks = ["a", "b", "c", "d"];
+        let t: MerkleTree = MerkleTree::build(&blocks);
+
+        let proof = t.batch_proof(&[0, 1, 2, 3]);
+        assert!(proof.verify(&blocks, super::DefaultHasher::new()));
+    }
+
+    #[test]
+    fn test_batch_proof_fails_for_wrong_value() {
+        let blocks = ["a", "b", "c", "d"];
+        let t: MerkleTree = MerkleTree::build(&blocks);
+
+        let proof = t.batch_proof(&[0, 1]);
+        assert!(!proof.verify(&["a", "not b"], super::DefaultHasher::new()));
+    }
+
+    #[test]
+    fn test_update_leaf_changes_root_hash() {
+        let block = "Hello World";
+        let mut t: MerkleTree = MerkleTree::build(&[block, block]);
+        let old_root = t.root_hash();
+
+        t.update_leaf(0, &"Bye, bye");
+
+        assert!(t.root_hash() != old_root);
+        assert!(t.verify(0, &"Bye, bye"));
+        assert!(!t.verify(0, &block));
+    }
+
+    #[test]
+    fn test_update_leaf_matches_full_rebuild() {
+        let mut t: MerkleTree = MerkleTree::build(&["a", "b", "c", "d"]);
+        t.update_leaf(2, &"z");
+
+        let rebuilt: MerkleTree = MerkleTree::build(&["a", "b", "z", "d"]);
+
+        assert_eq!(t.root_hash(), rebuilt.root_hash());
+    }
+
+    #[test]
+    fn test_update_leaf_matches_full_rebuild_for_non_power_of_two_leaves() {
+        let mut t: MerkleTree = MerkleTree::build(&["a", "b", "c"]);
+        t.update_leaf(2, &"z");
+
+        let rebuilt: MerkleTree = MerkleTree::build(&["a", "b", "z"]);
+
+        assert_eq!(t.root_hash(), rebuilt.root_hash());
+        assert!(t.verify(2, &"z"));
+    }
+
+    #[test]
+    fn test_batch_proof_fails_for_wrong_number_of_values() {
+        let blocks = ["a", "b", "c", "d"];
+        let t: MerkleTree = MerkleTree::build(&blocks);
+
+        let proof = t.batch_proof(&[0, 1]);
+        assert!(!proof.verify(&["a"], super::DefaultHasher::new()));
+    }
+
+    #[test]
+    fn test_batch_proof_verifies_empty_subset() {
+        let blocks = ["a", "b", "c", "d"];
+        let t: MerkleTree = MerkleTree::build(&blocks);
+
+        let proof = t.batch_proof(&[]);
+        let empty: [&str; 0] = [];
+        assert!(proof.verify(&empty, super::DefaultHasher::new()));
+    }
+
+    #[test]
+    fn test_root_hash256_roundtrips_through_hex_and_base64() {
+        let block = "Hello World";
+        let t: MerkleTree = MerkleTree::build(&[block, block]);
+        let root = t.root_hash256().unwrap();
+
+        assert_eq!(root, super::Hash256::from_hex(&root.to_hex()).unwrap());
+        assert_eq!(root, super::Hash256::from_base64(&root.to_base64()).unwrap());
+    }
+
+    #[test]
+    fn test_hash256_from_hex_rejects_wrong_length() {
+        assert_eq!(Err(super::ParseError::InvalidLength), super::Hash256::from_hex("ab"));
+    }
+
+    #[test]
+    fn test_hash256_from_hex_rejects_invalid_characters() {
+        let not_hex = "zz".repeat(32);
+        assert_eq!(Err(super::ParseError::InvalidEncoding), super::Hash256::from_hex(&not_hex));
+    }
+
+    #[test]
+    fn test_incremental_tree_root_matches_full_rebuild() {
+        let blocks = ["a", "b", "c", "d"];
+
+        let mut inc: IncrementalTree = IncrementalTree::new();
+        for block in &blocks {
+            inc.append(block);
+        }
+
+        let t: MerkleTree = MerkleTree::build(&blocks);
+
+        assert_eq!(Some(t.root_hash()), inc.root());
+    }
+
+    #[test]
+    fn test_incremental_tree_witness_verifies_appended_leaves() {
+        let blocks = ["a", "b", "c", "d"];
+
+        let mut inc: IncrementalTree = IncrementalTree::new();
+        for block in &blocks {
+            inc.append(block);
+        }
+
+        for (i, block) in blocks.iter().enumerate() {
+            let witness = inc.witness(i).unwrap();
+            assert!(witness.verify(block, super::DefaultHasher::new()));
+        }
+    }
+
+    #[test]
+    fn test_incremental_tree_witness_root_matches_root_for_single_leaf() {
+        let mut inc: IncrementalTree = IncrementalTree::new();
+        inc.append(&"a");
+
+        let witness = inc.witness(0).unwrap();
+        assert!(witness.verify(&"a", super::DefaultHasher::new()));
+        assert_eq!(Some(witness.root_hash.clone()), inc.root());
+    }
+
+    #[test]
+    fn test_incremental_tree_witness_out_of_range_is_none() {
+        let mut inc: IncrementalTree = IncrementalTree::new();
+        inc.append(&"a");
+        assert!(inc.witness(1).is_none());
+    }
+
+    #[test]
+    fn test_incremental_tree_root_is_none_before_any_append() {
+        let mut inc: IncrementalTree = IncrementalTree::new();
+        assert!(inc.root().is_none());
+    }
+
+    #[test]
+    fn test_prune_keeps_proof_for_kept_leaf() {
+        use super::MerkleTreePruner;
+
+        let blocks = ["a", "b", "c", "d", "e", "f", "g", "h"];
+        let mut t: MerkleTree = MerkleTree::build(&blocks);
+
+        let mut pruner = MerkleTreePruner::new(7, 8);
+        pruner.keep_leaf(0);
+        t.prune(&pruner);
+
+        let proof = t.proof(0).unwrap();
+        assert!(proof.verify(&"a", super::DefaultHasher::new()));
+    }
+
+    #[test]
+    fn test_prune_drops_unrequested_internal_nodes() {
+        use super::MerkleTreePruner;
+
+        let blocks = ["a", "b", "c", "d", "e", "f", "g", "h"];
+        let mut t: MerkleTree = MerkleTree::build(&blocks);
+
+        let mut pruner = MerkleTreePruner::new(7, 8);
+        pruner.keep_leaf(0);
+        t.prune(&pruner);
+
+        // leaf 5's authentication path needs the internal hash covering
+        // leaves 6 and 7, which was never requested and so was dropped.
+        let proof = t.proof(5).unwrap();
+        assert!(!proof.verify(&"f", super::DefaultHasher::new()));
+    }
+
+    #[test]
+    fn test_partial_tree_extracts_root_and_matched_leaves() {
+        let blocks = ["a", "b", "c", "d"];
+        let t: MerkleTree = MerkleTree::build(&blocks);
+        let partial = t.partial(&[0, 2]);
+
+        let (root, mut matches) = partial.extract_root_and_matches(super::DefaultHasher::new()).unwrap();
+        assert_eq!(root, t.root_hash());
+
+        matches.sort_by_key(|&(index, _)| index);
+        let mut hasher = super::DefaultHasher::new();
+        assert_eq!(matches,
+                   vec![(0, super::hash_leaf_node(&"a", &mut hasher)),
+                        (2, super::hash_leaf_node(&"c", &mut hasher))]);
+    }
+
+    #[test]
+    fn test_partial_tree_verifies_single_leaf_subset_of_five() {
+        let blocks = ["a", "b", "c", "d", "e"];
+        let t: MerkleTree = MerkleTree::build(&blocks);
+        let partial = t.partial(&[0]);
+
+        let (root, matches) = partial.extract_root_and_matches(super::DefaultHasher::new()).unwrap();
+        assert_eq!(root, t.root_hash());
+        assert_eq!(matches.len(), 1);
+
+        let mut hasher = super::DefaultHasher::new();
+        assert_eq!(matches[0], (0, super::hash_leaf_node(&"a", &mut hasher)));
+    }
+
+    #[test]
+    fn test_partial_tree_ignores_out_of_range_indices() {
+        let blocks = ["a", "b", "c", "d"];
+        let t: MerkleTree = MerkleTree::build(&blocks);
+        let partial = t.partial(&[0, 99]);
+
+        let (root, matches) = partial.extract_root_and_matches(super::DefaultHasher::new()).unwrap();
+        assert_eq!(root, t.root_hash());
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].0, 0);
+    }
+
+    #[test]
+    fn test_partial_tree_with_no_matches_only_carries_the_root() {
+        let blocks = ["a", "b", "c", "d"];
+        let t: MerkleTree = MerkleTree::build(&blocks);
+        let partial = t.partial(&[]);
+
+        let (root, matches) = partial.extract_root_and_matches(super::DefaultHasher::new()).unwrap();
+        assert_eq!(root, t.root_hash());
+        assert!(matches.is_empty());
+    }
+
+    #[test]
+    fn test_partial_tree_roundtrips_through_bytes() {
+        let blocks = ["a", "b", "c", "d", "e"];
+        let t: MerkleTree = MerkleTree::build(&blocks);
+        let partial = t.partial(&[0, 3]);
+
+        let wire = partial.to_bytes();
+        let decoded = super::PartialTree::from_bytes(&wire).unwrap();
+
+        let (root, mut matches) = decoded.extract_root_and_matches(super::DefaultHasher::new()).unwrap();
+        assert_eq!(root, t.root_hash());
+
+        matches.sort_by_key(|&(index, _)| index);
+        let mut hasher = super::DefaultHasher::new();
+        assert_eq!(matches,
+                   vec![(0, super::hash_leaf_node(&"a", &mut hasher)),
+                        (3, super::hash_leaf_node(&"d", &mut hasher))]);
+    }
+
+    #[test]
+    fn test_partial_tree_from_bytes_rejects_truncated_input() {
+        let blocks = ["a", "b", "c", "d"];
+        let t: MerkleTree = MerkleTree::build(&blocks);
+        let partial = t.partial(&[0, 2]);
+
+        let mut wire = partial.to_bytes();
+        wire.truncate(wire.len() - 1);
+
+        assert_eq!(super::PartialTree::from_bytes(&wire).unwrap_err(), super::PartialTreeError::Malformed);
+    }
+
+    #[test]
+    fn test_partial_tree_from_bytes_rejects_bogus_hash_count_without_large_alloc() {
+        // count_leaves=0, num_flags=0, num_hashes=u64::MAX, hash_len=0: a
+        // tiny payload claiming a huge number of zero-length hashes must
+        // be rejected as malformed, not by trying to reserve space for
+        // that many hashes up front.
+        let mut wire = vec![0u8; 8 + 8];
+        wire.extend_from_slice(&u64::max_value().to_be_bytes());
+        wire.extend_from_slice(&0u64.to_be_bytes());
+
+        assert_eq!(super::PartialTree::from_bytes(&wire).unwrap_err(), super::PartialTreeError::Malformed);
+    }
+
+    #[test]
+    fn test_partial_tree_extract_rejects_truncated_hashes() {
+        let blocks = ["a", "b", "c", "d"];
+        let t: MerkleTree = MerkleTree::build(&blocks);
+        let partial = t.partial(&[0, 2]);
+
+        // A flags/hashes pair that runs out mid-traversal is exactly the
+        // shape a tampered-with or truncated-in-transit PartialTree would
+        // have, even if it happened to decode cleanly.
+        let mut truncated = partial.clone();
+        truncated.hashes.pop();
+
+        assert_eq!(truncated.extract_root_and_matches(super::DefaultHasher::new()),
+                   Err(super::PartialTreeError::Truncated));
+    }
 }